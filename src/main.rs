@@ -16,8 +16,9 @@
 extern crate clap;
 
 use clap::{App, AppSettings, Arg};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::fmt;
-use std::collections::BTreeMap;
+use std::str::FromStr;
 
 fn main() {
 
@@ -34,7 +35,12 @@ fn main() {
              .short("d")
              .long("dups")
              .help(
-                 "Show all moves when they are duplicates"))
+                 "Deprecated, no-op: the state search now keeps only a \
+                  shortest back-pointer per state rather than every \
+                  sequence that reaches it, so individual duplicate \
+                  sequences can no longer be listed here. The aggregate \
+                  dup count is still reported on every run except \
+                  --solve."))
         .arg(Arg::with_name("all")
              .short("a")
              .long("all")
@@ -45,11 +51,46 @@ fn main() {
              .long("bests")
              .help(
                  "Show all of the best candidates, not just the first"))
+        .arg(Arg::with_name("solve")
+             .short("s")
+             .long("solve")
+             .takes_value(true)
+             .help(
+                 "Find the shortest move sequence to the given target state, \
+                  e.g. \"2>,0|,1<,3|\""))
+        .arg(Arg::with_name("dead")
+             .long("dead")
+             .takes_value(true)
+             .help(
+                 "States that are illegal to pass through, separated by \
+                  ';', e.g. \"(2>,0|,1<,3|);(0|,0|,0|,0|)\""))
+        .arg(Arg::with_name("symmetry")
+             .long("symmetry")
+             .help(
+                 "Fold states that are equivalent under rotation of the \
+                  wheels, and report the resulting equivalence classes"))
         .get_matches();
 
-    // This tree will hold all resulting states of the lock, keeping with
-    // them the various sequences that got us there.
-    let mut all = BTreeMap::new();
+    let dead: BTreeSet<Lock> = match matches.value_of("dead") {
+        Some(values) => values.split(';').map(|v| {
+            v.parse().unwrap_or_else(|err| {
+                panic!("Invalid dead state {:?}: {}", v, err)
+            })
+        }).collect(),
+        None => BTreeSet::new(),
+    };
+
+    if let Some(target) = matches.value_of("solve") {
+        let target: Lock = target.parse().unwrap_or_else(|err| {
+            panic!("Invalid target state {:?}: {}", target, err)
+        });
+
+        match solve(&target, &dead) {
+            Some(seq) => println!("{}", MoveSeq(seq)),
+            None => println!("No path found to {}", target),
+        }
+        return;
+    }
 
     // Get the argument for the maximum number of steps to try.  Since the
     // lock accepts arbitrary-length sequences, we need to limit the search
@@ -58,35 +99,54 @@ fn main() {
     let max = matches.value_of("max").unwrap_or("10")
         .parse::<u64>().unwrap();
 
-    let show_dups = matches.is_present("dups");
     let show_all = matches.is_present("all");
     let show_bests = matches.is_present("bests");
+    let show_symmetry = matches.is_present("symmetry");
+
+    let start = Lock::new();
+
+    // This tree will hold every state reachable within `max` moves,
+    // keeping only the predecessor that first reached it (giving a
+    // shortest path) along with a running count of how many sequences
+    // (of any length up to `max`) land on it.  This is the memory-frugal
+    // DP-path-reconstruction trick: one parent link per state instead of
+    // every whole path, with the displayed sequence rebuilt lazily by
+    // walking the links back to the start.
+    let mut all: BTreeMap<Lock, Target> = BTreeMap::new();
+
+    // `frontier` holds, for the current move count, how many distinct
+    // sequences of exactly that length land on each state.  Expanding it
+    // one level at a time replaces the old exponential enumeration (which
+    // iterated every `0 .. 2^(2*moves)` binary number) with a BFS-style
+    // frontier whose size is bounded by the lock's reachable state count.
+    let mut frontier: BTreeMap<Lock, u64> = BTreeMap::new();
+    frontier.insert(start.clone(), 1);
+
+    for _ in 0..max {
+        let mut next: BTreeMap<Lock, u64> = BTreeMap::new();
+
+        for (lock, &cnt) in &frontier {
+            for dir in 0..4u8 {
+                let mut nlock = lock.clone();
+                nlock.slide(dir);
+
+                if dead.contains(&nlock) {
+                    continue;
+                }
 
-    // Iterate through the number of moves, starting with single moves.
-    for moves in 1..(max+1) {
-        // Since there are 4 possibilities at each step, iterating through
-        // a 2^(2*moves) binary number, and using each pair of bits will
-        // give us all moves of that number of steps.
-        for binary in 0u64 .. (1 << 2*moves) {
-            // For a given move, create a `Lock` to simulate it, apply the
-            // moves, and then store it in the map based on the resulting
-            // Lock state.
-            let mut lock = Lock::new();
-            let mut tmp = binary;
-            let mut seq = vec![];
-            for _ in 0..moves {
-                lock.slide((tmp & 3) as u8);
-                seq.push((tmp & 3) as u8);
-                tmp >>= 2;
+                all.entry(nlock.clone()).or_insert_with(|| Target {
+                    count: 0,
+                    pred: (lock.clone(), dir),
+                });
+                *next.entry(nlock).or_insert(0) += cnt;
             }
-            let ent = all.entry(lock).or_insert_with(|| Target {
-                count: 0,
-                seq: MoveSeq(seq.clone()),
-                all: vec![],
-            });
-            ent.all.push(MoveSeq(seq));
-            ent.count += 1;
         }
+
+        for (lock, cnt) in &next {
+            all.get_mut(lock).unwrap().count += cnt;
+        }
+
+        frontier = next;
     }
 
     println!("For up to {} moves", max);
@@ -95,7 +155,7 @@ fn main() {
     println!("{} Uniques", all.len());
 
     // Count up all of the duplicates.
-    let dups: usize = all.values().map(|x| x.count - 1).sum();
+    let dups: u64 = all.values().map(|x| x.count - 1).sum();
     println!("{} dups", dups);
 
     // Extract all of the moves, and sort them so that the ones with the
@@ -103,35 +163,49 @@ fn main() {
     // number of steps involved.  When choosing a combination, no security
     // is gained by using a longer sequence, since a shorter one would be
     // found first in a brute-force search.
-    let mut moves: Vec<_> = all.iter().collect();
-    moves.sort_by(|a, b| a.1.seq.0.len().cmp(&b.1.seq.0.len()));
+    let mut moves: Vec<_> = all.iter()
+        .map(|(lock, target)| (lock, target, target.sequence(&all, &start)))
+        .collect();
+    moves.sort_by_key(|m| m.2.0.len());
     moves.sort_by_key(|m| m.1.count);
 
+    if show_symmetry {
+        // Group states by their rotational-symmetry canonical form, and
+        // for each class keep only the shortest representative sequence.
+        let mut classes: BTreeMap<Lock, (&Lock, &MoveSeq)> = BTreeMap::new();
+        for (lock, _target, seq) in &moves {
+            classes.entry(lock.canonical())
+                .and_modify(|(best_lock, best_seq)| {
+                    if seq.0.len() < best_seq.0.len() {
+                        *best_lock = lock;
+                        *best_seq = seq;
+                    }
+                })
+                .or_insert_with(|| (lock, seq));
+        }
+
+        println!("{} equivalence classes", classes.len());
+        for (canon, (lock, seq)) in &classes {
+            println!("{} -> {} ({})", canon, lock, seq);
+        }
+        return;
+    }
+
     if show_all {
-        for &(lock, target) in &moves {
-            println!("{} ({:4} target) {:-2} ({})", lock, target.count, target.seq.0.len(), target.seq);
-            if show_dups && target.count > 1 {
-                for mv in &target.all {
-                    println!("   {}", mv);
-                }
-            }
+        for (lock, target, seq) in &moves {
+            println!("{} ({:4} target) {:-2} ({})", lock, target.count, seq.0.len(), seq);
         }
     }
 
     // Find the best move, a move with the fewest number of conflicts that
     // is the shortest.
     let best_count = moves[0].1.count;
-    for &(lock, target) in &moves {
+    for (lock, target, seq) in &moves {
         if target.count != best_count {
             break;
         }
 
-        println!("Best: {} ({} target) ({})", lock, target.count, target.seq);
-        if show_dups && target.count > 1 {
-            for mv in &target.all {
-                println!("   {}", mv);
-            }
-        }
+        println!("Best: {} ({} target) ({})", lock, target.count, seq);
 
         if !show_bests {
             break;
@@ -141,12 +215,30 @@ fn main() {
 
 /// How we got to a state.
 struct Target {
-    /// How many moves (up to the max) arrive at this move.
-    count: usize,
-    /// The first sequence we encountered that got here.
-    seq: MoveSeq,
-    /// All of the sequences for this move.
-    all: Vec<MoveSeq>,
+    /// How many sequences (of any length up to the max) arrive at this
+    /// state.
+    count: u64,
+    /// The state and move that first reached this state, giving the
+    /// shortest path to it.
+    pred: (Lock, u8),
+}
+
+impl Target {
+    /// Reconstruct the shortest move sequence that reaches this state by
+    /// walking the predecessor chain back to `start`.
+    fn sequence(&self, all: &BTreeMap<Lock, Target>, start: &Lock) -> MoveSeq {
+        let mut seq = vec![];
+        let (mut prev, mut mv) = self.pred.clone();
+        seq.push(mv);
+        while &prev != start {
+            let pred = &all[&prev].pred;
+            mv = pred.1;
+            prev = pred.0.clone();
+            seq.push(mv);
+        }
+        seq.reverse();
+        MoveSeq(seq)
+    }
 }
 
 /// The state of a single wheel within the lock.  The wheel can be in one
@@ -242,6 +334,67 @@ impl Lock {
             wh.reset();
         }
     }
+
+    /// Compute the canonical representative of this state's rotational
+    /// symmetry class: the four wheels are arranged in a cycle, so
+    /// rotating every wheel the same number of positions around that
+    /// cycle produces a structurally equivalent lock.  The canonical
+    /// form is the lexicographically smallest of the four cyclic
+    /// rotations of `wheels`.
+    fn canonical(&self) -> Lock {
+        (0..4).map(|k| {
+            let mut wheels = self.wheels;
+            wheels.rotate_left(k);
+            Lock { wheels }
+        }).min().unwrap()
+    }
+}
+
+/// Perform a breadth-first search over the lock's state graph, starting
+/// from a freshly reset lock, and return the shortest sequence of moves
+/// that reaches `target`, or `None` if it is unreachable without passing
+/// through a state in `dead`.
+fn solve(target: &Lock, dead: &BTreeSet<Lock>) -> Option<Vec<u8>> {
+    let start = Lock::new();
+    if &start == target {
+        return Some(vec![]);
+    }
+
+    // Maps a state to the state and move that first reached it.
+    let mut preds: BTreeMap<Lock, (Lock, u8)> = BTreeMap::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(start.clone());
+
+    while let Some(cur) = queue.pop_front() {
+        for dir in 0..4u8 {
+            let mut nlock = cur.clone();
+            nlock.slide(dir);
+
+            if nlock == start || preds.contains_key(&nlock) || dead.contains(&nlock) {
+                continue;
+            }
+
+            preds.insert(nlock.clone(), (cur.clone(), dir));
+
+            if &nlock == target {
+                // Walk the predecessor chain back to the start, building
+                // up the move sequence in reverse.
+                let mut seq = vec![dir];
+                let mut at = cur;
+                while at != start {
+                    let (prev, mv) = preds[&at].clone();
+                    seq.push(mv);
+                    at = prev;
+                }
+                seq.reverse();
+                return Some(seq);
+            }
+
+            queue.push_back(nlock);
+        }
+    }
+
+    None
 }
 
 fn prior(wheel: u8) -> u8 {
@@ -260,6 +413,55 @@ fn next(wheel: u8) -> u8 {
     }
 }
 
+/// Parse a wheel from its `Display` form, e.g. "2>", "0|", "1<".
+impl FromStr for Wheel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Wheel, String> {
+        let mut chars = s.chars();
+        let pos = chars
+            .next()
+            .and_then(|c| c.to_digit(10))
+            .ok_or_else(|| format!("Invalid wheel position: {:?}", s))?;
+        let shift = match chars.next() {
+            Some('<') => -1,
+            Some('|') => 0,
+            Some('>') => 1,
+            _ => return Err(format!("Invalid wheel shift: {:?}", s)),
+        };
+        if chars.next().is_some() {
+            return Err(format!("Invalid wheel: {:?}", s));
+        }
+        Ok(Wheel::new(pos as u8, shift))
+    }
+}
+
+/// Parse a lock from its `Display` form, e.g. "(2>,0|,1<,3|)".  The
+/// surrounding parens are optional, to make it easier to pass a state on
+/// the command line.
+impl FromStr for Lock {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Lock, String> {
+        let s = s.trim().trim_start_matches('(').trim_end_matches(')');
+
+        let mut wheels = [Wheel::new(0, 0); 4];
+        let mut count = 0;
+        for (i, part) in s.split(',').enumerate() {
+            if i >= wheels.len() {
+                return Err(format!("Too many wheels in {:?}", s));
+            }
+            wheels[i] = part.parse()?;
+            count += 1;
+        }
+        if count != wheels.len() {
+            return Err(format!("Expected {} wheels, got {}", wheels.len(), count));
+        }
+
+        Ok(Lock { wheels })
+    }
+}
+
 impl fmt::Display for Lock {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut first = true;